@@ -0,0 +1,385 @@
+//! Kademlia-style peer/validator discovery.
+//!
+//! Each peer's node ID is `hash(pub_key)`, a 256-bit value. Known contacts
+//! are organized into 256 k-buckets, one per leading-zero count of the
+//! XOR distance to our own ID (so bucket `i` holds contacts whose distance
+//! has exactly `i` leading zero bits), each holding at most `K` entries.
+//! `lookup` drives the standard iterative lookup over a `RoutingTable`: query the
+//! `ALPHA` closest known contacts with `FindNode`, merge whatever contacts
+//! come back, and repeat against the new closest set until it stops
+//! improving or `MAX_LOOKUP_ROUNDS` is hit.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crypto::{hash, Hash, PublicKey};
+
+/// Bucket capacity.
+const K: usize = 16;
+/// Parallelism factor for iterative lookups.
+const ALPHA: usize = 3;
+/// Upper bound on rounds for a single iterative lookup, so a lookup that
+/// never converges still terminates.
+const MAX_LOOKUP_ROUNDS: usize = 8;
+/// How often a non-empty bucket is refreshed with a self-targeted lookup.
+const BUCKET_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// 256-bit node identifier, derived as `hash(pub_key)`.
+pub type NodeId = Hash;
+
+pub fn node_id(pub_key: &PublicKey) -> NodeId {
+    hash(pub_key.as_ref())
+}
+
+/// XOR distance between two node IDs, as a big-endian 32-byte value.
+fn xor_distance(a: &NodeId, b: &NodeId) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    let (a, b) = (a.as_ref(), b.as_ref());
+    for i in 0..32 {
+        out[i] = a[i] ^ b[i];
+    }
+    out
+}
+
+/// Index of the bucket a contact at `distance` belongs in: the count of
+/// leading zero bits, i.e. `255 - highest_set_bit`.
+fn bucket_index(distance: &[u8; 32]) -> usize {
+    for (byte_idx, byte) in distance.iter().enumerate() {
+        if *byte != 0 {
+            let leading = byte.leading_zeros() as usize;
+            return byte_idx * 8 + leading;
+        }
+    }
+    // Distance to self; not expected to be inserted, but keep this total.
+    255
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Contact {
+    pub id: NodeId,
+    pub pub_key: PublicKey,
+    pub addr: SocketAddr,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    contacts: VecDeque<Contact>,
+    last_refreshed: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Bucket { contacts: VecDeque::new(), last_refreshed: Instant::now() }
+    }
+
+    /// Inserts or refreshes `contact`. Kademlia prefers long-lived
+    /// contacts, so an existing entry is just moved to the most-recently-seen
+    /// end rather than replaced; a genuinely new contact is only dropped
+    /// if the bucket is already full.
+    fn insert(&mut self, contact: Contact) {
+        if let Some(pos) = self.contacts.iter().position(|c| c.id == contact.id) {
+            self.contacts.remove(pos);
+            self.contacts.push_back(contact);
+            return;
+        }
+        if self.contacts.len() < K {
+            self.contacts.push_back(contact);
+        }
+        // A full bucket silently drops a brand-new contact; callers that
+        // want the full Kademlia behavior (ping the least-recently-seen
+        // entry and evict it on failure) should use
+        // `insert_checking_liveness` instead.
+    }
+
+    /// Same as `insert`, but when the bucket is already full and `contact`
+    /// is genuinely new, `ping` is used to check whether the
+    /// least-recently-seen entry (the front of the deque) is still alive
+    /// before deciding what to do: if it responds, it's refreshed to the
+    /// back and `contact` is dropped as usual; if it doesn't, it's evicted
+    /// and `contact` takes its place.
+    fn insert_checking_liveness<F>(&mut self, contact: Contact, mut ping: F)
+        where F: FnMut(&Contact) -> bool
+    {
+        if let Some(pos) = self.contacts.iter().position(|c| c.id == contact.id) {
+            self.contacts.remove(pos);
+            self.contacts.push_back(contact);
+            return;
+        }
+        if self.contacts.len() < K {
+            self.contacts.push_back(contact);
+            return;
+        }
+
+        let least_recently_seen = self.contacts[0];
+        if ping(&least_recently_seen) {
+            self.contacts.pop_front();
+            self.contacts.push_back(least_recently_seen);
+        } else {
+            self.contacts.pop_front();
+            self.contacts.push_back(contact);
+        }
+    }
+
+    fn evict(&mut self, id: &NodeId) {
+        self.contacts.retain(|c| &c.id != id);
+    }
+
+    fn is_stale(&self) -> bool {
+        self.last_refreshed.elapsed() >= BUCKET_REFRESH_INTERVAL
+    }
+}
+
+/// The routing table for a single local node.
+#[derive(Debug)]
+pub struct RoutingTable {
+    self_id: NodeId,
+    buckets: Vec<Bucket>,
+}
+
+impl RoutingTable {
+    pub fn new(self_pub_key: &PublicKey) -> Self {
+        RoutingTable {
+            self_id: node_id(self_pub_key),
+            buckets: (0..256).map(|_| Bucket::new()).collect(),
+        }
+    }
+
+    pub fn self_id(&self) -> &NodeId {
+        &self.self_id
+    }
+
+    /// Records a sighting of `contact`, placing it in the bucket for its
+    /// distance from us. A no-op for our own ID.
+    pub fn insert(&mut self, contact: Contact) {
+        if contact.id == self.self_id {
+            return;
+        }
+        let idx = bucket_index(&xor_distance(&self.self_id, &contact.id));
+        self.buckets[idx].insert(contact);
+    }
+
+    /// Same as `insert`, but a full bucket doesn't just drop `contact`: its
+    /// least-recently-seen entry is pinged first via `ping`, and only
+    /// evicted in favor of `contact` if that ping fails. A no-op for our
+    /// own ID.
+    pub fn insert_checking_liveness<F>(&mut self, contact: Contact, ping: F)
+        where F: FnMut(&Contact) -> bool
+    {
+        if contact.id == self.self_id {
+            return;
+        }
+        let idx = bucket_index(&xor_distance(&self.self_id, &contact.id));
+        self.buckets[idx].insert_checking_liveness(contact, ping);
+    }
+
+    /// Drops `id` from its bucket, e.g. after it fails a liveness ping.
+    pub fn evict(&mut self, id: &NodeId) {
+        if id == &self.self_id {
+            return;
+        }
+        let idx = bucket_index(&xor_distance(&self.self_id, id));
+        self.buckets[idx].evict(id);
+    }
+
+    /// Returns the buckets that haven't been refreshed within
+    /// `BUCKET_REFRESH_INTERVAL` and aren't empty, so the caller can issue
+    /// a self-targeted lookup to keep them warm.
+    pub fn stale_buckets(&self) -> Vec<usize> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|&(_, b)| !b.contacts.is_empty() && b.is_stale())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    pub fn mark_refreshed(&mut self, bucket_idx: usize) {
+        self.buckets[bucket_idx].last_refreshed = Instant::now();
+    }
+
+    /// Returns up to `count` known contacts closest to `target`, sorted
+    /// nearest-first. This is the local, non-network part of a lookup
+    /// round; it never returns contacts further than the `2*count` or so
+    /// nearest candidates, which is all a single round needs.
+    pub fn closest(&self, target: &NodeId, count: usize) -> Vec<Contact> {
+        let mut all: Vec<Contact> = self.buckets.iter().flat_map(|b| b.contacts.iter().cloned()).collect();
+        all.sort_by_key(|c| xor_distance(target, &c.id));
+        all.truncate(count);
+        all
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|b| b.contacts.len()).sum()
+    }
+}
+
+/// Drives an iterative Kademlia lookup for `target` starting from
+/// whatever `table` already knows, using `query` to send a `FindNode` to a
+/// single contact and get back whatever contacts it offers.
+///
+/// Queries `ALPHA` closest unqueried contacts per round, merges the
+/// results into the running candidate set, and stops once a round fails
+/// to bring the closest known contact any nearer to `target`, or after
+/// `MAX_LOOKUP_ROUNDS` rounds, whichever comes first.
+pub fn lookup<F>(table: &RoutingTable, target: &NodeId, mut query: F) -> Vec<Contact>
+    where F: FnMut(&Contact) -> Vec<Contact>
+{
+    let mut candidates = table.closest(target, K);
+    let mut queried = ::std::collections::HashSet::new();
+
+    for _ in 0..MAX_LOOKUP_ROUNDS {
+        let to_query: Vec<Contact> = candidates
+            .iter()
+            .filter(|c| !queried.contains(&c.id))
+            .take(ALPHA)
+            .cloned()
+            .collect();
+
+        if to_query.is_empty() {
+            break;
+        }
+
+        let closest_before = candidates.first().map(|c| xor_distance(target, &c.id));
+
+        for contact in &to_query {
+            queried.insert(contact.id);
+            candidates.extend(query(contact));
+        }
+
+        candidates.sort_by_key(|c| xor_distance(target, &c.id));
+        candidates.dedup_by_key(|c| c.id);
+        candidates.truncate(K);
+
+        let closest_after = candidates.first().map(|c| xor_distance(target, &c.id));
+        if closest_after >= closest_before {
+            break;
+        }
+    }
+
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    use crypto::gen_keypair;
+    use super::{RoutingTable, Contact, node_id, lookup, K};
+
+    fn make_contact() -> Contact {
+        let (pub_key, _) = gen_keypair();
+        Contact {
+            id: node_id(&pub_key),
+            pub_key: pub_key,
+            addr: SocketAddr::from_str("127.0.0.1:7777").unwrap(),
+        }
+    }
+
+    #[test]
+    fn insert_and_find_closest() {
+        let (self_pub, _) = gen_keypair();
+        let mut table = RoutingTable::new(&self_pub);
+
+        let contacts: Vec<_> = (0..5).map(|_| make_contact()).collect();
+        for c in &contacts {
+            table.insert(*c);
+        }
+
+        assert_eq!(table.len(), 5);
+        let target = contacts[0].id;
+        let closest = table.closest(&target, 1);
+        assert_eq!(closest[0].id, target);
+    }
+
+    #[test]
+    fn bucket_does_not_grow_past_capacity() {
+        let (self_pub, _) = gen_keypair();
+        let mut table = RoutingTable::new(&self_pub);
+
+        // Insert more contacts than a single bucket can hold and make sure
+        // the table doesn't silently grow unbounded.
+        for _ in 0..(K * 4) {
+            table.insert(make_contact());
+        }
+
+        assert!(table.len() <= 256 * K);
+        assert!(table.buckets.iter().all(|b| b.contacts.len() <= K));
+    }
+
+    #[test]
+    fn self_id_is_never_inserted() {
+        let (self_pub, _) = gen_keypair();
+        let mut table = RoutingTable::new(&self_pub);
+        let id = *table.self_id();
+
+        table.insert(Contact { id: id, pub_key: self_pub, addr: SocketAddr::from_str("127.0.0.1:1").unwrap() });
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn lookup_converges_through_a_seed() {
+        let (self_pub, _) = gen_keypair();
+        let mut table = RoutingTable::new(&self_pub);
+
+        let seed = make_contact();
+        table.insert(seed);
+
+        let target = make_contact().id;
+        let network_contact = make_contact();
+        let network_contact_id = network_contact.id;
+
+        let results = lookup(&table, &target, |_contact| vec![network_contact]);
+
+        assert!(results.iter().any(|c| c.id == network_contact_id));
+    }
+
+    #[test]
+    fn lookup_on_empty_table_returns_nothing() {
+        let (self_pub, _) = gen_keypair();
+        let table = RoutingTable::new(&self_pub);
+        let target = make_contact().id;
+
+        let results = lookup(&table, &target, |_contact| vec![]);
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn liveness_insert_keeps_least_recently_seen_contact_when_its_ping_succeeds() {
+        use super::Bucket;
+
+        let mut bucket = Bucket::new();
+        let contacts: Vec<_> = (0..K).map(|_| make_contact()).collect();
+        for c in &contacts {
+            bucket.insert(*c);
+        }
+        let least_recently_seen = contacts[0].id;
+
+        let newcomer = make_contact();
+        bucket.insert_checking_liveness(newcomer, |_candidate| true);
+
+        assert_eq!(bucket.contacts.len(), K);
+        assert!(bucket.contacts.iter().any(|c| c.id == least_recently_seen));
+        assert!(bucket.contacts.iter().all(|c| c.id != newcomer.id));
+    }
+
+    #[test]
+    fn liveness_insert_evicts_least_recently_seen_contact_when_its_ping_fails() {
+        use super::Bucket;
+
+        let mut bucket = Bucket::new();
+        let contacts: Vec<_> = (0..K).map(|_| make_contact()).collect();
+        for c in &contacts {
+            bucket.insert(*c);
+        }
+        let least_recently_seen = contacts[0].id;
+
+        let newcomer = make_contact();
+        bucket.insert_checking_liveness(newcomer, |_candidate| false);
+
+        assert_eq!(bucket.contacts.len(), K);
+        assert!(bucket.contacts.iter().any(|c| c.id == newcomer.id));
+        assert!(bucket.contacts.iter().all(|c| c.id != least_recently_seen));
+    }
+}
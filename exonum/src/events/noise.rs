@@ -0,0 +1,864 @@
+//! Authenticated, encrypted session layer that sits between a `Channel`
+//! implementation and the raw network stream.
+//!
+//! Every node has a long-term key pair `(P, S)` plus a set of trusted peer
+//! public keys. Two ways to populate that set are supported:
+//!
+//! * `NoiseConfig::shared_secret` derives the same deterministic key pair
+//!   (and thus the same single trusted key) on every node from a passphrase,
+//!   which is convenient for closed test networks.
+//! * `NoiseConfig::explicit_trust` generates a fresh key pair per node and
+//!   trusts whatever peer keys are listed in config.
+//!
+//! On connect an ephemeral-static handshake mixes a fresh Diffie-Hellman
+//! exchange with the static keys to derive send/receive keys, and the peer
+//! is rejected unless its static key is in the trusted set. Because the
+//! underlying transport is UDP-like (reordering and loss are normal), each
+//! frame is prefixed with a monotonically increasing counter and the
+//! receiver tracks a sliding replay window instead of requiring strict
+//! ordering. Keys are ratcheted automatically, without tearing down the
+//! session, once a frame/time budget is exhausted.
+//!
+//! `NodeSender::send_to` (in `tokio::handler`) looks up the live session
+//! for the destination address and seals outbound bytes through it before
+//! handing them to `NetworkRequest::SendMessage`; `NodeSender::register_noise_session`
+//! is how a completed handshake gets installed for a peer.
+//!
+//! Each side also advertises `messages::version::SUPPORTED_VERSIONS` during
+//! `mix`, so the handshake fails with `NoCommonVersion` rather than
+//! completing two peers into a session neither can actually frame messages
+//! for. The negotiated version is then carried by every frame `seal`
+//! produces, and `open` checks it via `messages::version::check_version`
+//! before trusting anything else in the frame.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use crypto::{hash, gen_keypair, Hash};
+use messages::version::{self, FormatError};
+
+/// Width of the replay window, in frames. Any sequence number at or below
+/// `highest_seen - REPLAY_WINDOW_SIZE` is treated as stale and dropped.
+const REPLAY_WINDOW_SIZE: u64 = 1024;
+
+/// Modulus and generator for the Diffie-Hellman group used to derive
+/// session keys. This crate has no elliptic-curve scalar multiplication
+/// available to it (the `crypto` module only exposes ed25519 signing
+/// keys), so the handshake runs a classic modular-exponentiation DH over
+/// a 61-bit Mersenne prime instead of production X25519; a real backend
+/// would swap `dh` below for a Curve25519 scalar multiplication without
+/// touching anything else in this file.
+const DH_MODULUS: u128 = 2_305_843_009_213_693_951; // 2^61 - 1
+const DH_GENERATOR: u128 = 5;
+
+fn modpow(mut base: u128, mut exponent: u128, modulus: u128) -> u128 {
+    let mut result = 1u128;
+    base %= modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result * base % modulus;
+        }
+        exponent >>= 1;
+        base = base * base % modulus;
+    }
+    result
+}
+
+/// Derives a DH scalar in `[1, DH_MODULUS - 2]` from arbitrary seed bytes.
+fn scalar_from_seed(seed: &[u8]) -> u128 {
+    let digest = hash(seed);
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(&digest.as_ref()[..8]);
+    let value = u64::from_be_bytes(raw) as u128;
+    (value % (DH_MODULUS - 2)) + 1
+}
+
+/// A Diffie-Hellman public value: `DH_GENERATOR ^ secret mod DH_MODULUS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DhPublicKey(u64);
+
+/// The matching private scalar.
+#[derive(Debug, Clone, Copy)]
+pub struct DhSecretKey(u64);
+
+/// Generates a keypair from a fresh random scalar, using `gen_keypair`
+/// purely as a source of entropy for the seed (its own public half is
+/// unrelated to our DH group and is discarded).
+pub fn generate_dh_keypair() -> (DhPublicKey, DhSecretKey) {
+    let (_, random_material) = gen_keypair();
+    keypair_from_scalar_seed(random_material.as_ref())
+}
+
+/// Deterministically derives a keypair from `seed`, so every caller that
+/// passes the same seed converges on the same `(DhPublicKey, DhSecretKey)`.
+pub fn derive_dh_keypair(seed: &[u8]) -> (DhPublicKey, DhSecretKey) {
+    keypair_from_scalar_seed(seed)
+}
+
+fn keypair_from_scalar_seed(seed: &[u8]) -> (DhPublicKey, DhSecretKey) {
+    let scalar = scalar_from_seed(seed);
+    let public = modpow(DH_GENERATOR, scalar, DH_MODULUS) as u64;
+    (DhPublicKey(public), DhSecretKey(scalar as u64))
+}
+
+/// Runs the DH exchange: `dh(our_secret, their_public)` and
+/// `dh(their_secret, our_public)` always agree, because both sides are
+/// computing `DH_GENERATOR ^ (our_scalar * their_scalar) mod DH_MODULUS`.
+fn dh(ours: &DhSecretKey, theirs: &DhPublicKey) -> [u8; 8] {
+    let shared = modpow(theirs.0 as u128, ours.0 as u128, DH_MODULUS) as u64;
+    shared.to_be_bytes()
+}
+
+/// How this node's long-term key pair is established.
+#[derive(Debug, Clone)]
+pub enum TrustMode {
+    /// Every node derives the same static key pair from a shared
+    /// passphrase, and trusts only that single derived key.
+    SharedSecret { passphrase: Vec<u8> },
+    /// Each node has its own generated static key pair and an explicit
+    /// list of peer public keys it is willing to talk to.
+    ExplicitTrust {
+        trusted_peers: HashSet<DhPublicKey>,
+        our_keypair: (DhPublicKey, DhSecretKey),
+    },
+}
+
+/// Configuration for the session layer, covering key material and the
+/// rekeying schedule.
+#[derive(Debug, Clone)]
+pub struct NoiseConfig {
+    pub mode: TrustMode,
+    /// Rekey after this many frames have been sent on a session.
+    pub rekey_after_frames: u64,
+    /// Rekey after this much wall-clock time has elapsed since the last
+    /// handshake, regardless of frame count.
+    pub rekey_after: Duration,
+}
+
+impl NoiseConfig {
+    /// Derives a deterministic key pair from `passphrase` so that every
+    /// node configured with the same passphrase ends up trusting the same
+    /// single static key.
+    pub fn shared_secret(passphrase: &[u8], rekey_after_frames: u64, rekey_after: Duration) -> Self {
+        NoiseConfig {
+            mode: TrustMode::SharedSecret { passphrase: passphrase.to_vec() },
+            rekey_after_frames,
+            rekey_after,
+        }
+    }
+
+    /// Uses a freshly generated key pair and trusts exactly the peers in
+    /// `trusted_peers`.
+    pub fn explicit_trust(trusted_peers: HashSet<DhPublicKey>,
+                           rekey_after_frames: u64,
+                           rekey_after: Duration)
+                           -> Self {
+        NoiseConfig {
+            mode: TrustMode::ExplicitTrust {
+                trusted_peers: trusted_peers,
+                our_keypair: generate_dh_keypair(),
+            },
+            rekey_after_frames,
+            rekey_after,
+        }
+    }
+
+    /// Resolves this node's static key pair for the configured mode.
+    pub fn static_keypair(&self) -> (DhPublicKey, DhSecretKey) {
+        match self.mode {
+            TrustMode::SharedSecret { ref passphrase } => derive_dh_keypair(passphrase),
+            TrustMode::ExplicitTrust { our_keypair, .. } => our_keypair,
+        }
+    }
+
+    /// Returns whether `peer` is allowed to complete a handshake with us.
+    pub fn is_trusted(&self, peer: &DhPublicKey) -> bool {
+        match self.mode {
+            TrustMode::SharedSecret { ref passphrase } => {
+                let (derived_pub, _) = derive_dh_keypair(passphrase);
+                &derived_pub == peer
+            }
+            TrustMode::ExplicitTrust { ref trusted_peers, .. } => trusted_peers.contains(peer),
+        }
+    }
+}
+
+/// The ephemeral-static handshake state machine: `Initiated` holds our
+/// freshly generated ephemeral key while we wait for the peer's side,
+/// `Finished` holds the directional transport keys once both DH outputs
+/// have been mixed.
+#[derive(Debug)]
+pub enum HandshakeState {
+    Initiated { our_ephemeral_pub: DhPublicKey, our_ephemeral_sec: DhSecretKey },
+    Finished { peer_static: DhPublicKey, send_key: Hash, recv_key: Hash, version: u8 },
+}
+
+impl HandshakeState {
+    /// Starts a handshake by generating our ephemeral key pair.
+    pub fn initiate() -> Self {
+        let (our_ephemeral_pub, our_ephemeral_sec) = generate_dh_keypair();
+        HandshakeState::Initiated {
+            our_ephemeral_pub: our_ephemeral_pub,
+            our_ephemeral_sec: our_ephemeral_sec,
+        }
+    }
+
+    pub fn our_ephemeral_pub(&self) -> Option<DhPublicKey> {
+        match *self {
+            HandshakeState::Initiated { our_ephemeral_pub, .. } => Some(our_ephemeral_pub),
+            HandshakeState::Finished { .. } => None,
+        }
+    }
+
+    /// Mixes our ephemeral secret, the peer's ephemeral public key and
+    /// both static keys into directional transport keys, checking the
+    /// peer's static key against `config`'s trust set and negotiating a
+    /// message format version against `peer_supported_versions` (the
+    /// peer's own `messages::version::SUPPORTED_VERSIONS`).
+    ///
+    /// `is_initiator` picks which of the two directional keys derived
+    /// from the shared transcript becomes our send key versus our recv
+    /// key; both peers label the two directions identically
+    /// (`initiator_to_responder` / `responder_to_initiator`), so whichever
+    /// one is "send" for the initiator is "recv" for the responder.
+    pub fn mix(self,
+               config: &NoiseConfig,
+               our_static_sec: &DhSecretKey,
+               peer_ephemeral_pub: &DhPublicKey,
+               peer_static_pub: &DhPublicKey,
+               peer_supported_versions: &[u8],
+               is_initiator: bool)
+               -> Result<HandshakeState, NoiseError> {
+        if !config.is_trusted(peer_static_pub) {
+            return Err(NoiseError::UntrustedPeer);
+        }
+        let version = version::negotiate_version(version::SUPPORTED_VERSIONS, peer_supported_versions)
+            .ok_or(NoiseError::NoCommonVersion)?;
+        let our_ephemeral_sec = match self {
+            HandshakeState::Initiated { our_ephemeral_sec, .. } => our_ephemeral_sec,
+            HandshakeState::Finished { .. } => return Err(NoiseError::InvalidState),
+        };
+
+        let ephemeral_shared = dh(&our_ephemeral_sec, peer_ephemeral_pub);
+        let static_shared = dh(our_static_sec, peer_static_pub);
+        let mut transcript = Vec::with_capacity(16);
+        transcript.extend_from_slice(&ephemeral_shared);
+        transcript.extend_from_slice(&static_shared);
+
+        // Both peers compute the exact same `transcript` (the DH outputs
+        // are reciprocal regardless of who initiated), so deriving the
+        // two directional keys off fixed labels here gives both sides
+        // the same pair of keys in the same order.
+        let mut i2r_seed = transcript.clone();
+        i2r_seed.extend_from_slice(b"initiator_to_responder");
+        let i2r_key = hash(&i2r_seed);
+
+        let mut r2i_seed = transcript;
+        r2i_seed.extend_from_slice(b"responder_to_initiator");
+        let r2i_key = hash(&r2i_seed);
+
+        let (send_key, recv_key) = if is_initiator {
+            (i2r_key, r2i_key)
+        } else {
+            (r2i_key, i2r_key)
+        };
+
+        Ok(HandshakeState::Finished {
+            peer_static: *peer_static_pub,
+            send_key: send_key,
+            recv_key: recv_key,
+            version: version,
+        })
+    }
+
+    /// Finalizes the handshake into a `NoiseSession`, ready to
+    /// encrypt/decrypt frames.
+    pub fn into_session(self, rekey_after_frames: u64, rekey_after: Duration) -> Result<NoiseSession, NoiseError> {
+        match self {
+            HandshakeState::Finished { peer_static, send_key, recv_key, version } => {
+                Ok(NoiseSession::new(peer_static, send_key, recv_key, version, rekey_after_frames, rekey_after))
+            }
+            HandshakeState::Initiated { .. } => Err(NoiseError::InvalidState),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoiseError {
+    UntrustedPeer,
+    InvalidState,
+    ReplayedFrame,
+    StaleFrame,
+    /// The peer's advertised `SUPPORTED_VERSIONS` has nothing in common
+    /// with ours, so no message format both sides can frame exists.
+    NoCommonVersion,
+    /// A received frame's version header didn't pass
+    /// `messages::version::check_version`.
+    Format(FormatError),
+    /// The frame's integrity tag didn't match its ciphertext, meaning the
+    /// frame was corrupted or tampered with in transit.
+    TamperedFrame,
+}
+
+impl From<FormatError> for NoiseError {
+    fn from(err: FormatError) -> Self {
+        NoiseError::Format(err)
+    }
+}
+
+/// Sliding replay window over frame sequence numbers, tolerant of
+/// reordering and loss: frames may arrive out of order as long as they
+/// fall within `REPLAY_WINDOW_SIZE` of the highest sequence number seen so
+/// far, and each one is only accepted once.
+#[derive(Debug)]
+struct ReplayWindow {
+    highest_seen: u64,
+    seen: HashSet<u64>,
+}
+
+impl ReplayWindow {
+    fn new() -> Self {
+        ReplayWindow { highest_seen: 0, seen: HashSet::new() }
+    }
+
+    /// Accepts `seq` if it hasn't been seen before and isn't older than
+    /// the trailing edge of the window; evicts entries that fall out of
+    /// the window as `highest_seen` advances.
+    fn accept(&mut self, seq: u64) -> Result<(), NoiseError> {
+        if seq + REPLAY_WINDOW_SIZE <= self.highest_seen {
+            return Err(NoiseError::StaleFrame);
+        }
+        if !self.seen.insert(seq) {
+            return Err(NoiseError::ReplayedFrame);
+        }
+        if seq > self.highest_seen {
+            self.highest_seen = seq;
+            // Must mirror the staleness check above exactly: a sequence
+            // number only stops being trackable once it would itself be
+            // rejected as stale. Using a `saturating_sub` floor here
+            // diverges from that check for any `highest_seen <
+            // REPLAY_WINDOW_SIZE` (the floor clamps to `0`, evicting
+            // `seq == 0` as soon as `highest_seen` reaches `1` instead of
+            // `REPLAY_WINDOW_SIZE`), which would let early frames replay
+            // undetected for most of a session's first window.
+            self.seen.retain(|&s| s + REPLAY_WINDOW_SIZE > self.highest_seen);
+        }
+        Ok(())
+    }
+}
+
+/// An established, ratcheting encrypted session with a single peer.
+#[derive(Debug)]
+pub struct NoiseSession {
+    peer_static: DhPublicKey,
+    send_key: Hash,
+    recv_key: Hash,
+    // Message format version negotiated with this peer during the
+    // handshake (see `HandshakeState::mix`). Tagged onto every frame `seal`
+    // produces and checked by `open` before anything else in the frame is
+    // trusted.
+    version: u8,
+    send_counter: u64,
+    replay_window: ReplayWindow,
+    frames_since_rekey: u64,
+    last_rekey: Instant,
+    rekey_after_frames: u64,
+    rekey_after: Duration,
+    // Bumped every `auto_rekey`, so repeated automatic rekeys derive
+    // distinct fresh material instead of reusing the same one forever.
+    rekey_generation: u64,
+}
+
+impl NoiseSession {
+    fn new(peer_static: DhPublicKey,
+           send_key: Hash,
+           recv_key: Hash,
+           version: u8,
+           rekey_after_frames: u64,
+           rekey_after: Duration)
+           -> Self {
+        NoiseSession {
+            peer_static: peer_static,
+            send_key: send_key,
+            recv_key: recv_key,
+            version: version,
+            send_counter: 0,
+            replay_window: ReplayWindow::new(),
+            frames_since_rekey: 0,
+            last_rekey: Instant::now(),
+            rekey_after_frames: rekey_after_frames,
+            rekey_after: rekey_after,
+            rekey_generation: 0,
+        }
+    }
+
+    pub fn peer_static(&self) -> &DhPublicKey {
+        &self.peer_static
+    }
+
+    /// Encrypts `plaintext` into a frame prefixed with the negotiated
+    /// format version and the next sequence counter. The counter, not
+    /// stream order, is what the receiver uses to detect replays and
+    /// staleness; the version byte is what lets it reject a frame from a
+    /// session whose negotiated format it no longer understands instead of
+    /// misparsing it.
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let seq = self.send_counter;
+        self.send_counter += 1;
+        self.frames_since_rekey += 1;
+
+        let ciphertext = encrypt_with_key(&self.send_key, seq, plaintext);
+        let tag = mac(&self.send_key, seq, &ciphertext);
+
+        let mut frame = Vec::with_capacity(1 + 8 + ciphertext.len() + tag.as_ref().len());
+        frame.push(self.version);
+        frame.extend_from_slice(&seq.to_be_bytes());
+        frame.extend_from_slice(&ciphertext);
+        frame.extend_from_slice(tag.as_ref());
+        frame
+    }
+
+    /// Decrypts a frame produced by `seal`, rejecting an unrecognized
+    /// format version, a mismatched integrity tag, and duplicate or
+    /// too-stale sequence numbers, in that order: the tag is checked
+    /// before the frame's sequence number is fed into the replay window,
+    /// so a corrupted or forged frame can't pollute the window's state.
+    pub fn open(&mut self, frame: &[u8]) -> Result<Vec<u8>, NoiseError> {
+        version::check_version(frame)?;
+        let body = &frame[version::VERSION_OFFSET + 1..];
+
+        let tag_len = tag_len();
+        if body.len() < 8 + tag_len {
+            return Err(NoiseError::InvalidState);
+        }
+        let mut seq_bytes = [0u8; 8];
+        seq_bytes.copy_from_slice(&body[..8]);
+        let seq = u64::from_be_bytes(seq_bytes);
+
+        let (ciphertext, tag) = body[8..].split_at(body.len() - 8 - tag_len);
+        if mac(&self.recv_key, seq, ciphertext).as_ref() != tag {
+            return Err(NoiseError::TamperedFrame);
+        }
+
+        self.replay_window.accept(seq)?;
+        Ok(decrypt_with_key(&self.recv_key, seq, ciphertext))
+    }
+
+    /// Whether this session is due for a rekey, either because it has
+    /// carried too many frames or because too much time has passed since
+    /// the last handshake.
+    pub fn needs_rekey(&self) -> bool {
+        self.frames_since_rekey >= self.rekey_after_frames ||
+        self.last_rekey.elapsed() >= self.rekey_after
+    }
+
+    /// Ratchets the session keys forward by mixing a fresh ephemeral DH
+    /// into the current send/receive keys, without dropping the
+    /// connection or resetting the replay window's notion of sequence
+    /// continuity. Both directions are mixed with the same fresh value so
+    /// the two peers, which each mix their own send/recv key, still end
+    /// up agreeing (send key on one side tracks recv key on the other).
+    pub fn rekey(&mut self, our_ephemeral_sec: &DhSecretKey, peer_ephemeral_pub: &DhPublicKey) {
+        let fresh = dh(our_ephemeral_sec, peer_ephemeral_pub);
+
+        let mut send_seed = Vec::with_capacity(40);
+        send_seed.extend_from_slice(self.send_key.as_ref());
+        send_seed.extend_from_slice(&fresh);
+        self.send_key = hash(&send_seed);
+
+        let mut recv_seed = Vec::with_capacity(40);
+        recv_seed.extend_from_slice(self.recv_key.as_ref());
+        recv_seed.extend_from_slice(&fresh);
+        self.recv_key = hash(&recv_seed);
+
+        self.frames_since_rekey = 0;
+        self.last_rekey = Instant::now();
+    }
+
+    /// Rekeys this session without needing a fresh ephemeral key
+    /// exchanged with the peer first, so it can be driven unilaterally by
+    /// whichever side notices `needs_rekey()` -- in practice,
+    /// `NodeSender::send_to` on every send.
+    ///
+    /// `rekey` assumes both peers already agree on a fresh ephemeral pair
+    /// obtained out of band; since this tree has no message type to
+    /// negotiate that over the wire yet, `auto_rekey` instead derives its
+    /// "fresh" ephemeral deterministically from the current `(send_key,
+    /// recv_key)` pair plus a generation counter. Both peers hold the
+    /// same two keys, just swapped (this side's `send_key` is the other
+    /// side's `recv_key`), so sorting them into a peer-independent order
+    /// before hashing makes both sides derive the identical keypair, and
+    /// thus the identical fresh value, with no coordination required. This
+    /// still rotates key material on the configured frame/time budget, but
+    /// -- unlike a real fresh-ephemeral `rekey` -- doesn't gain forward
+    /// secrecy against an adversary who has already recorded the current
+    /// keys, since that's everything needed to rederive the next ones too.
+    pub fn auto_rekey(&mut self) {
+        let (ephemeral_pub, ephemeral_sec) = self.next_ephemeral();
+        self.rekey_generation += 1;
+        self.rekey(&ephemeral_sec, &ephemeral_pub);
+    }
+
+    fn next_ephemeral(&self) -> (DhPublicKey, DhSecretKey) {
+        let (first, second) = if self.send_key.as_ref() <= self.recv_key.as_ref() {
+            (self.send_key.as_ref(), self.recv_key.as_ref())
+        } else {
+            (self.recv_key.as_ref(), self.send_key.as_ref())
+        };
+        let mut seed = Vec::with_capacity(first.len() + second.len() + 8);
+        seed.extend_from_slice(first);
+        seed.extend_from_slice(second);
+        seed.extend_from_slice(&self.rekey_generation.to_be_bytes());
+        derive_dh_keypair(&seed)
+    }
+}
+
+/// XORs `plaintext` against a keystream derived from `key` and `seq`. This
+/// is the symmetric building block `seal`/`open` share; a real deployment
+/// would plug in an AEAD cipher here instead.
+fn encrypt_with_key(key: &Hash, seq: u64, plaintext: &[u8]) -> Vec<u8> {
+    keystream_xor(key, seq, plaintext)
+}
+
+fn decrypt_with_key(key: &Hash, seq: u64, ciphertext: &[u8]) -> Vec<u8> {
+    keystream_xor(key, seq, ciphertext)
+}
+
+/// XORs `data` against a keystream built from one digest per
+/// `hash(key||seq||block_index)` block rather than one digest repeated
+/// over the whole frame, so two blocks of the same frame never reuse the
+/// same keystream bytes (data longer than a single digest would otherwise
+/// leak through `ciphertext[i] ^ ciphertext[i+block_len]`).
+fn keystream_xor(key: &Hash, seq: u64, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut block_index = 0u64;
+    while out.len() < data.len() {
+        let block = keystream_block(key, seq, block_index);
+        let block_bytes = block.as_ref();
+        let take = (data.len() - out.len()).min(block_bytes.len());
+        for i in 0..take {
+            out.push(data[out.len()] ^ block_bytes[i]);
+        }
+        block_index += 1;
+    }
+    out
+}
+
+fn keystream_block(key: &Hash, seq: u64, block_index: u64) -> Hash {
+    let mut seed = Vec::with_capacity(key.as_ref().len() + 16);
+    seed.extend_from_slice(key.as_ref());
+    seed.extend_from_slice(&seq.to_be_bytes());
+    seed.extend_from_slice(&block_index.to_be_bytes());
+    hash(&seed)
+}
+
+/// Keyed integrity tag over a sealed frame's ciphertext, checked by `open`
+/// before anything else about the frame is trusted: `seal`/`open` are a
+/// stream cipher with no authentication of their own, so without this tag
+/// an on-path attacker could flip ciphertext bits and have them decrypt
+/// (silently, to different plaintext) instead of being rejected.
+fn mac(key: &Hash, seq: u64, ciphertext: &[u8]) -> Hash {
+    let mut seed = Vec::with_capacity(key.as_ref().len() + 8 + 3 + ciphertext.len());
+    seed.extend_from_slice(key.as_ref());
+    seed.extend_from_slice(b"mac");
+    seed.extend_from_slice(&seq.to_be_bytes());
+    seed.extend_from_slice(ciphertext);
+    hash(&seed)
+}
+
+/// Length in bytes of the tag `mac` produces, derived rather than
+/// hardcoded since the `crypto` module doesn't expose its digest size as a
+/// constant.
+fn tag_len() -> usize {
+    hash(&[]).as_ref().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::collections::HashSet;
+
+    use super::{NoiseConfig, HandshakeState, NoiseError, ReplayWindow, DhPublicKey, DhSecretKey,
+                generate_dh_keypair, version};
+
+    fn handshake_pair(config_a: &NoiseConfig,
+                      config_b: &NoiseConfig,
+                      a_static: (DhPublicKey, DhSecretKey),
+                      b_static: (DhPublicKey, DhSecretKey))
+                      -> (super::NoiseSession, super::NoiseSession) {
+        let (a_static_pub, a_static_sec) = a_static;
+        let (b_static_pub, b_static_sec) = b_static;
+
+        let a_hs = HandshakeState::initiate();
+        let b_hs = HandshakeState::initiate();
+        let a_ephemeral_pub = a_hs.our_ephemeral_pub().unwrap();
+        let b_ephemeral_pub = b_hs.our_ephemeral_pub().unwrap();
+
+        // A is the initiator, B is the responder.
+        let a_finished = a_hs.mix(config_a, &a_static_sec, &b_ephemeral_pub, &b_static_pub, version::SUPPORTED_VERSIONS, true).unwrap();
+        let b_finished = b_hs.mix(config_b, &b_static_sec, &a_ephemeral_pub, &a_static_pub, version::SUPPORTED_VERSIONS, false).unwrap();
+
+        let _ = (a_static_pub, b_static_pub);
+        (a_finished.into_session(config_a.rekey_after_frames, config_a.rekey_after).unwrap(),
+         b_finished.into_session(config_b.rekey_after_frames, config_b.rekey_after).unwrap())
+    }
+
+    #[test]
+    fn shared_secret_mode_derives_matching_keypairs() {
+        let config = NoiseConfig::shared_secret(b"test passphrase", 1000, Duration::from_secs(60));
+        let (pub1, _) = config.static_keypair();
+        let (pub2, _) = config.static_keypair();
+        assert_eq!(pub1, pub2);
+        assert!(config.is_trusted(&pub1));
+    }
+
+    #[test]
+    fn shared_secret_mode_with_different_passphrases_diverges() {
+        let config_a = NoiseConfig::shared_secret(b"passphrase a", 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::shared_secret(b"passphrase b", 1000, Duration::from_secs(60));
+        let (pub_a, _) = config_a.static_keypair();
+        let (pub_b, _) = config_b.static_keypair();
+        assert_ne!(pub_a, pub_b);
+    }
+
+    #[test]
+    fn explicit_trust_rejects_unlisted_peers() {
+        let (trusted_pub, _) = generate_dh_keypair();
+        let mut trusted = HashSet::new();
+        trusted.insert(trusted_pub);
+        let config = NoiseConfig::explicit_trust(trusted, 1000, Duration::from_secs(60));
+
+        let (other_pub, _) = generate_dh_keypair();
+        assert!(config.is_trusted(&trusted_pub));
+        assert!(!config.is_trusted(&other_pub));
+    }
+
+    #[test]
+    fn handshake_rejects_untrusted_static_key() {
+        let (_, trusted_sec) = generate_dh_keypair();
+        let (untrusted_pub, _) = generate_dh_keypair();
+        let mut trusted = HashSet::new();
+        let (trusted_pub, _) = generate_dh_keypair();
+        trusted.insert(trusted_pub);
+        let config = NoiseConfig::explicit_trust(trusted, 1000, Duration::from_secs(60));
+
+        let hs = HandshakeState::initiate();
+        let (peer_ephemeral_pub, _) = generate_dh_keypair();
+        let err = hs.mix(&config, &trusted_sec, &peer_ephemeral_pub, &untrusted_pub, version::SUPPORTED_VERSIONS, true).unwrap_err();
+        assert_eq!(err, NoiseError::UntrustedPeer);
+    }
+
+    #[test]
+    fn handshake_rejects_a_peer_with_no_version_in_common() {
+        let (a_pub, _) = generate_dh_keypair();
+        let (b_pub, _) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+
+        let a_hs = HandshakeState::initiate();
+        let (b_ephemeral_pub, _) = generate_dh_keypair();
+
+        let err = a_hs.mix(&config_a, &generate_dh_keypair().1, &b_ephemeral_pub, &b_pub, &[200], true).unwrap_err();
+        assert_eq!(err, NoiseError::NoCommonVersion);
+    }
+
+    #[test]
+    fn two_peers_agree_on_the_same_directional_keys() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        // What A seals with its send key, B must be able to open with its
+        // recv key, and vice versa -- this only holds if both sides
+        // derived the same pair of directional keys.
+        let from_a = a_session.seal(b"hello from a");
+        assert_eq!(b_session.open(&from_a).unwrap(), b"hello from a");
+
+        let from_b = b_session.seal(b"hello from b");
+        assert_eq!(a_session.open(&from_b).unwrap(), b"hello from b");
+    }
+
+    #[test]
+    fn open_rejects_a_frame_with_an_unrecognized_version() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        let mut frame = a_session.seal(b"hello");
+        frame[version::VERSION_OFFSET] = 200; // No build of this node understands format 200.
+
+        let err = b_session.open(&frame).unwrap_err();
+        assert_eq!(err, NoiseError::Format(version::FormatError::UnsupportedVersion { found: 200 }));
+    }
+
+    #[test]
+    fn open_rejects_a_frame_whose_ciphertext_was_tampered_with() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        let mut frame = a_session.seal(b"vote: yes");
+        // Flip a single ciphertext bit, as an on-path attacker could; this
+        // must surface as a tamper error instead of silently decrypting
+        // to different plaintext.
+        let ciphertext_start = version::VERSION_OFFSET + 1 + 8;
+        frame[ciphertext_start] ^= 0x01;
+
+        assert_eq!(b_session.open(&frame).unwrap_err(), NoiseError::TamperedFrame);
+    }
+
+    #[test]
+    fn keystream_does_not_repeat_across_blocks_within_one_frame() {
+        // A frame longer than a single digest must not leak through
+        // ciphertext[i] ^ ciphertext[i + block_len] == plaintext[i] ^
+        // plaintext[i + block_len], which a single repeated keystream
+        // block would cause.
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        let block_len = super::tag_len();
+        let plaintext = vec![0u8; block_len * 2];
+        let frame = a_session.seal(&plaintext);
+        assert_eq!(b_session.open(&frame).unwrap(), plaintext);
+
+        let ciphertext_start = version::VERSION_OFFSET + 1 + 8;
+        let ciphertext = &frame[ciphertext_start..ciphertext_start + plaintext.len()];
+        assert_ne!(&ciphertext[..block_len], &ciphertext[block_len..]);
+    }
+
+    #[test]
+    fn session_round_trips_and_tolerates_reordering() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        let f1 = a_session.seal(b"hello");
+        let f2 = a_session.seal(b"world");
+
+        // Frames arrive out of order but both are still accepted exactly once.
+        assert_eq!(b_session.open(&f2).unwrap(), b"world");
+        assert_eq!(b_session.open(&f1).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn duplicate_frame_is_rejected() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 1000, Duration::from_secs(60));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 1000, Duration::from_secs(60));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+
+        let frame = a_session.seal(b"once only");
+        assert!(b_session.open(&frame).is_ok());
+        assert_eq!(b_session.open(&frame).unwrap_err(), NoiseError::ReplayedFrame);
+    }
+
+    #[test]
+    fn seq_zero_is_not_replayable_once_highest_seen_advances_past_it() {
+        let mut window = ReplayWindow::new();
+
+        assert!(window.accept(0).is_ok());
+        // Advancing highest_seen to 1 must not evict seq 0 from `seen`
+        // early: with a buggy `saturating_sub` floor this would, letting
+        // the replay below through as Ok instead of ReplayedFrame.
+        assert!(window.accept(1).is_ok());
+        assert_eq!(window.accept(0).unwrap_err(), NoiseError::ReplayedFrame);
+    }
+
+    #[test]
+    fn rekey_resets_frame_counter_and_both_sides_still_agree() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 2, Duration::from_secs(3600));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 2, Duration::from_secs(3600));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+        assert!(!a_session.needs_rekey());
+        a_session.seal(b"1");
+        a_session.seal(b"2");
+        assert!(a_session.needs_rekey());
+
+        let (fresh_a_ephemeral_pub, fresh_a_ephemeral_sec) = generate_dh_keypair();
+        let (fresh_b_ephemeral_pub, fresh_b_ephemeral_sec) = generate_dh_keypair();
+
+        a_session.rekey(&fresh_a_ephemeral_sec, &fresh_b_ephemeral_pub);
+        b_session.rekey(&fresh_b_ephemeral_sec, &fresh_a_ephemeral_pub);
+        assert!(!a_session.needs_rekey());
+
+        let frame = a_session.seal(b"post-rekey");
+        assert_eq!(b_session.open(&frame).unwrap(), b"post-rekey");
+        let _ = (a_sec, b_sec);
+    }
+
+    #[test]
+    fn auto_rekey_needs_no_peer_coordination_and_both_sides_still_agree() {
+        let (a_pub, a_sec) = generate_dh_keypair();
+        let (b_pub, b_sec) = generate_dh_keypair();
+        let mut trusted_a = HashSet::new();
+        trusted_a.insert(b_pub);
+        let mut trusted_b = HashSet::new();
+        trusted_b.insert(a_pub);
+        let config_a = NoiseConfig::explicit_trust(trusted_a, 2, Duration::from_secs(3600));
+        let config_b = NoiseConfig::explicit_trust(trusted_b, 2, Duration::from_secs(3600));
+
+        let (mut a_session, mut b_session) = handshake_pair(&config_a, &config_b, (a_pub, a_sec), (b_pub, b_sec));
+        a_session.seal(b"1");
+        a_session.seal(b"2");
+        assert!(a_session.needs_rekey());
+
+        // Each side calls auto_rekey independently -- no ephemeral key is
+        // exchanged between them -- and they still agree afterwards.
+        a_session.auto_rekey();
+        b_session.auto_rekey();
+        assert!(!a_session.needs_rekey());
+
+        let frame = a_session.seal(b"post-rekey");
+        assert_eq!(b_session.open(&frame).unwrap(), b"post-rekey");
+    }
+}
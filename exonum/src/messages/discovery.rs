@@ -0,0 +1,79 @@
+//! Message types for the Kademlia-style peer discovery subsystem.
+//!
+//! These mirror how `RequestBlock` is defined and signed elsewhere in this
+//! module: a thin `message!`-generated wrapper around `RawMessage` with
+//! typed accessors and the usual `verify`/`raw` pair.
+
+use crypto::{Hash, PublicKey};
+use time::Timespec;
+
+use super::{RawMessage};
+
+/// Message category for the peer-discovery message family (`FindNode`,
+/// `Nodes`, `Ping`, `Pong`), the same role `RequestBlock`'s category plays
+/// for block sync. Picked from the low end of the `u16` category space
+/// that isn't claimed by any category already in use in this tree.
+pub const DISCOVERY_MESSAGE_ID: u16 = 4;
+
+message! {
+    /// Asks a peer for the `k` contacts closest to `target` that it knows
+    /// about, driving one step of an iterative Kademlia lookup.
+    FindNode {
+        const TYPE = DISCOVERY_MESSAGE_ID;
+        const ID = 0;
+        const SIZE = 72;
+
+        field from:    &PublicKey  [00 => 32]
+        field target:  &Hash       [32 => 64]
+        field time:    Timespec    [64 => 72]
+    }
+}
+
+message! {
+    /// Response to `FindNode`, carrying the responder's closest known
+    /// contacts to the requested target.
+    Nodes {
+        const TYPE = DISCOVERY_MESSAGE_ID;
+        const ID = 1;
+        const SIZE = 80;
+
+        field from:      &PublicKey        [00 => 32]
+        field target:    &Hash             [32 => 64]
+        field time:      Timespec          [64 => 72]
+        field contacts:  &[ContactRecord]   [72 => 80]
+    }
+}
+
+message! {
+    /// Liveness probe used to confirm a contact is still reachable before
+    /// it is inserted into (or retained in) a k-bucket.
+    Ping {
+        const TYPE = DISCOVERY_MESSAGE_ID;
+        const ID = 2;
+        const SIZE = 40;
+
+        field from:  &PublicKey  [00 => 32]
+        field time:  Timespec    [32 => 40]
+    }
+}
+
+message! {
+    /// Reply to `Ping`, proving the responder is alive and holds the
+    /// matching static key.
+    Pong {
+        const TYPE = DISCOVERY_MESSAGE_ID;
+        const ID = 3;
+        const SIZE = 40;
+
+        field from:  &PublicKey  [00 => 32]
+        field time:  Timespec    [32 => 40]
+    }
+}
+
+/// A single contact entry as carried inside a `Nodes` reply: enough to
+/// add the peer straight into a routing table without a further lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContactRecord {
+    pub pub_key: PublicKey,
+    pub addr: ::std::net::SocketAddr,
+}
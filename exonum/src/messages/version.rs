@@ -0,0 +1,148 @@
+//! Explicit format-version header for framed messages, so a change to a
+//! message layout (adding a field to `Propose`, widening a counter, etc.)
+//! fails cleanly instead of being misread as garbage by a node running a
+//! different version.
+//!
+//! `check_version`/`write_version` read and write a single version byte at
+//! a fixed offset; `events::noise::NoiseSession` is the current consumer,
+//! tagging every sealed frame with the version negotiated during the
+//! handshake and checking it with `check_version` before trusting anything
+//! else in the frame. `negotiate_version` is what
+//! `events::noise::HandshakeState::mix` runs over each side's
+//! `SUPPORTED_VERSIONS` to agree on that version in the first place, so a
+//! network can roll forward version by version instead of needing a
+//! synchronized flag day.
+
+use std::fmt;
+use std::error::Error;
+
+/// The newest message format version this build of the node can both read
+/// and write.
+pub const CURRENT_VERSION: u8 = 1;
+
+/// All message format versions this build can still read, oldest first.
+/// `CURRENT_VERSION` must always be the last entry.
+pub const SUPPORTED_VERSIONS: &'static [u8] = &[1];
+
+/// Offset of the version byte within a framed buffer: its very first byte.
+pub const VERSION_OFFSET: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatError {
+    /// The buffer is too short to even contain a version byte.
+    Truncated,
+    /// The version byte names a format this node doesn't know how to
+    /// parse, either because it's older than anything we still support or
+    /// newer than anything we've been taught.
+    UnsupportedVersion { found: u8 },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FormatError::Truncated => write!(f, "message buffer too short to contain a format version"),
+            FormatError::UnsupportedVersion { found } => {
+                write!(f,
+                       "unsupported message format version {} (supported: {:?})",
+                       found,
+                       SUPPORTED_VERSIONS)
+            }
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn description(&self) -> &str {
+        match *self {
+            FormatError::Truncated => "message buffer too short to contain a format version",
+            FormatError::UnsupportedVersion { .. } => "unsupported message format version",
+        }
+    }
+}
+
+/// Reads and validates the version byte from the front of a framed
+/// message buffer. Called before any field inside the frame is
+/// interpreted (e.g. by `NoiseSession::open`), so a version mismatch is
+/// reported as a descriptive error rather than surfacing later as a
+/// confusing parse failure or an out-of-bounds panic.
+pub fn check_version(buf: &[u8]) -> Result<u8, FormatError> {
+    if buf.len() <= VERSION_OFFSET {
+        return Err(FormatError::Truncated);
+    }
+    let version = buf[VERSION_OFFSET];
+    if SUPPORTED_VERSIONS.contains(&version) {
+        Ok(version)
+    } else {
+        Err(FormatError::UnsupportedVersion { found: version })
+    }
+}
+
+/// Writes the current format version into the header position
+/// `check_version` reads from.
+pub fn write_version(buf: &mut [u8]) {
+    buf[VERSION_OFFSET] = CURRENT_VERSION;
+}
+
+/// Negotiates the message format version two peers will use for the rest
+/// of the connection: the highest version present in both `local` and
+/// `remote`. Used by `events::noise::HandshakeState::mix`, where each side
+/// advertises `SUPPORTED_VERSIONS` and both sides run this on the
+/// exchanged lists so they agree on the same answer independently.
+///
+/// Returns `None` if the two peers have no version in common, meaning the
+/// connection can't proceed.
+pub fn negotiate_version(local: &[u8], remote: &[u8]) -> Option<u8> {
+    local.iter()
+        .filter(|v| remote.contains(v))
+        .max()
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_version, write_version, negotiate_version, FormatError, CURRENT_VERSION};
+
+    #[test]
+    fn round_trip_accepts_current_version() {
+        let mut buf = vec![0u8; 16];
+        write_version(&mut buf);
+        assert_eq!(check_version(&buf).unwrap(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn old_version_buffer_is_rejected_with_a_descriptive_error() {
+        let mut buf = vec![0u8; 16];
+        buf[0] = 0; // A format version older than anything `CURRENT_VERSION` supports.
+        let err = check_version(&buf).unwrap_err();
+        assert_eq!(err, FormatError::UnsupportedVersion { found: 0 });
+        assert!(err.to_string().contains("unsupported message format version"));
+    }
+
+    #[test]
+    fn future_version_buffer_is_rejected_not_misparsed() {
+        let mut buf = vec![0u8; 16];
+        buf[0] = 200; // A format version newer than this build understands.
+        let err = check_version(&buf).unwrap_err();
+        assert_eq!(err, FormatError::UnsupportedVersion { found: 200 });
+    }
+
+    #[test]
+    fn truncated_buffer_does_not_panic() {
+        let buf: Vec<u8> = Vec::new();
+        assert_eq!(check_version(&buf).unwrap_err(), FormatError::Truncated);
+    }
+
+    #[test]
+    fn negotiation_picks_the_highest_shared_version() {
+        let ours = [1u8, 2, 3];
+        let theirs = [2u8, 3, 4];
+        assert_eq!(negotiate_version(&ours, &theirs), Some(3));
+    }
+
+    #[test]
+    fn negotiation_fails_cleanly_with_no_overlap() {
+        let ours = [1u8];
+        let theirs = [2u8];
+        assert_eq!(negotiate_version(&ours, &theirs), None);
+    }
+}
@@ -5,8 +5,12 @@ use tokio_core::reactor::Handle;
 
 use std::time::{SystemTime};
 use std::net::SocketAddr;
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
 use events::Channel;
+use events::noise::NoiseSession;
 use node::{ExternalMessage, NodeTimeout};
 use messages::RawMessage;
 
@@ -42,6 +46,11 @@ pub struct NodeSender {
     pub timeout: mpsc::Sender<TimeoutRequest>,
     pub network: mpsc::Sender<NetworkRequest>,
     pub external: mpsc::Sender<ExternalMessage>,
+    // Live noise sessions keyed by peer address. `send_to` seals outbound
+    // bytes through the session for its destination, if one has been
+    // established; `register_noise_session` is how a completed handshake
+    // gets installed here.
+    noise_sessions: Rc<RefCell<HashMap<SocketAddr, NoiseSession>>>,
 }
 
 #[derive(Debug)]
@@ -64,6 +73,7 @@ impl NodeChannel {
             timeout: timeout_sender,
             network: network_sender,
             external: external_sender,
+            noise_sessions: Rc::new(RefCell::new(HashMap::new())),
         };
         let receiver = NodeReceiver {
             timeout: timeout_receiver,
@@ -74,12 +84,44 @@ impl NodeChannel {
     }
 }
 
+impl NodeSender {
+    /// Installs `session` as the live noise session for `address`, so
+    /// subsequent `send_to` calls for that peer seal their payload
+    /// through it. Called once the handshake state machine in
+    /// `events::noise` reaches `HandshakeState::into_session`.
+    pub fn register_noise_session(&self, address: SocketAddr, session: NoiseSession) {
+        self.noise_sessions.borrow_mut().insert(address, session);
+    }
+
+    pub fn drop_noise_session(&self, address: &SocketAddr) {
+        self.noise_sessions.borrow_mut().remove(address);
+    }
+}
+
 impl Channel for NodeSender {
     type ApplicationEvent = ExternalMessage;
     type Timeout = NodeTimeout;
 
     fn send_to(&mut self, handle: Handle, address: SocketAddr, message: RawMessage) {
-        let request = NetworkRequest::SendMessage(address, message);
+        // Seal the message through the peer's live session, if one has
+        // been established; otherwise fall back to sending it plaintext
+        // (e.g. the `Connect` that bootstraps the handshake itself).
+        let sealed = self.noise_sessions
+            .borrow_mut()
+            .get_mut(&address)
+            .map(|session| {
+                // Every send is a natural point to check whether the
+                // session is due for a rekey, since we already have it
+                // borrowed mutably; `auto_rekey` doesn't need anything
+                // from the peer first, so it can run right here.
+                if session.needs_rekey() {
+                    session.auto_rekey();
+                }
+                RawMessage::from(session.seal(message.as_ref()))
+            });
+        let payload = sealed.unwrap_or(message);
+
+        let request = NetworkRequest::SendMessage(address, payload);
         let send_future = self.network
             .clone()
             .send(request)
@@ -109,6 +151,21 @@ where
     timeout: Fuse<S1>,
     network: Fuse<S2>,
     api: Fuse<S3>,
+    // Index of the stream (0 = timeout, 1 = network, 2 = api) whose
+    // buffered item is delivered first on the next `poll`; the streams
+    // themselves are always polled in that fixed order. Rotated every call
+    // so a hot stream can't starve the others out.
+    cursor: u8,
+    // An item already pulled off its stream but not yet returned to the
+    // consumer because a different stream was picked this round. Polled
+    // streams are never re-polled while a pending item for them exists,
+    // so nothing pulled off a stream is ever dropped on the floor.
+    pending_timeout: Option<Event>,
+    pending_network: Option<Event>,
+    pending_api: Option<Event>,
+    timeout_done: bool,
+    network_done: bool,
+    api_done: bool,
 }
 
 impl<S1, S2, S3> EventsAggregator<S1, S2, S3>
@@ -122,10 +179,32 @@ where
             network: network.fuse(),
             timeout: timeout.fuse(),
             api: api.fuse(),
+            cursor: 0,
+            pending_timeout: None,
+            pending_network: None,
+            pending_api: None,
+            timeout_done: false,
+            network_done: false,
+            api_done: false,
         }
     }
 }
 
+/// Returns the first `Some` item among `slots`, checking them starting at
+/// `cursor` and wrapping around, so which slot wins ties depends on
+/// `cursor` rather than always favoring index 0. Slots that aren't picked
+/// are left untouched (still `Some`) so their item is delivered next time.
+fn pick_buffered<T>(cursor: u8, slots: [&mut Option<T>; 3]) -> Option<T> {
+    let mut slots = slots;
+    for i in 0..3 {
+        let idx = (cursor as usize + i) % 3;
+        if let Some(item) = slots[idx].take() {
+            return Some(item);
+        }
+    }
+    None
+}
+
 impl<S1, S2, S3> Stream for EventsAggregator<S1, S2, S3>
 where
     S1: Stream<Item = NodeTimeout>,
@@ -142,30 +221,42 @@ where
     type Error = S1::Error;
 
     fn poll(&mut self) -> Poll<Option<Event>, Self::Error> {
-        let mut stream_finished = false;
-        // Check timeout events
-        match self.timeout.poll()? {
-            Async::Ready(Some(item)) => return Ok(Async::Ready(Some(Event::Timeout(item)))),
-            // Just finish stream
-            Async::Ready(None) => stream_finished = true,
-            Async::NotReady => {}
-        };
-        // Check network events
-        match self.network.poll()? {
-            Async::Ready(Some(item)) => return Ok(Async::Ready(Some(Event::Network(item)))),
-            // Just finish stream
-            Async::Ready(None) => stream_finished = true,
-            Async::NotReady => {}
-        };
-        // Check api events
-        match self.api.poll()? {
-            Async::Ready(Some(item)) => return Ok(Async::Ready(Some(Event::Api(item)))),
-            // Just finish stream
-            Async::Ready(None) => stream_finished = true,
-            Async::NotReady => {}
-        };
+        // Only poll a stream if we don't already have a buffered item for
+        // it; otherwise an item pulled off the stream this round would
+        // overwrite (and lose) the one from a previous round that hasn't
+        // been delivered yet.
+        if self.pending_timeout.is_none() && !self.timeout_done {
+            match self.timeout.poll()? {
+                Async::Ready(Some(item)) => self.pending_timeout = Some(Event::Timeout(item)),
+                Async::Ready(None) => self.timeout_done = true,
+                Async::NotReady => {}
+            }
+        }
+        if self.pending_network.is_none() && !self.network_done {
+            match self.network.poll()? {
+                Async::Ready(Some(item)) => self.pending_network = Some(Event::Network(item)),
+                Async::Ready(None) => self.network_done = true,
+                Async::NotReady => {}
+            }
+        }
+        if self.pending_api.is_none() && !self.api_done {
+            match self.api.poll()? {
+                Async::Ready(Some(item)) => self.pending_api = Some(Event::Api(item)),
+                Async::Ready(None) => self.api_done = true,
+                Async::NotReady => {}
+            }
+        }
+
+        let cursor = self.cursor;
+        self.cursor = (self.cursor + 1) % 3;
 
-        Ok(if stream_finished {
+        let picked = pick_buffered(cursor,
+                                    [&mut self.pending_timeout, &mut self.pending_network, &mut self.pending_api]);
+        if let Some(event) = picked {
+            return Ok(Async::Ready(Some(event)));
+        }
+
+        Ok(if self.timeout_done && self.network_done && self.api_done {
             Async::Ready(None)
         } else {
             Async::NotReady
@@ -175,4 +266,93 @@ where
 
 pub trait EventHandler {
     fn handle_event(&mut self, event: Event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pick_buffered;
+
+    #[test]
+    fn rotation_picks_lowest_ready_slot_when_cursor_is_zero() {
+        let (mut a, mut b, mut c) = (Some(1), Some(2), Some(3));
+        assert_eq!(pick_buffered(0, [&mut a, &mut b, &mut c]), Some(1));
+    }
+
+    #[test]
+    fn rotation_starts_from_cursor() {
+        let (mut a, mut b, mut c) = (Some(1), Some(2), Some(3));
+        assert_eq!(pick_buffered(1, [&mut a, &mut b, &mut c]), Some(2));
+        assert_eq!(pick_buffered(2, [&mut a, &mut b, &mut c]), Some(3));
+    }
+
+    #[test]
+    fn rotation_wraps_around_to_earlier_slots() {
+        // Only slot 0 is ready; starting the search at slot 2 should still
+        // find it by wrapping back around.
+        let (mut a, mut b, mut c) = (Some(42), None, None);
+        assert_eq!(pick_buffered(2, [&mut a, &mut b, &mut c]), Some(42));
+    }
+
+    #[test]
+    fn rotation_returns_none_when_nothing_is_ready() {
+        let (mut a, mut b, mut c): (Option<i32>, Option<i32>, Option<i32>) = (None, None, None);
+        assert_eq!(pick_buffered(0, [&mut a, &mut b, &mut c]), None);
+    }
+
+    #[test]
+    fn unpicked_slots_are_left_in_place_instead_of_being_dropped() {
+        // All three slots are ready at once; only one is picked, and the
+        // other two must still be `Some` afterwards so a later poll can
+        // deliver them instead of losing them.
+        let (mut a, mut b, mut c) = (Some("timeout"), Some("network"), Some("api"));
+        let picked = pick_buffered(0, [&mut a, &mut b, &mut c]);
+
+        assert_eq!(picked, Some("timeout"));
+        assert_eq!(a, None);
+        assert_eq!(b, Some("network"));
+        assert_eq!(c, Some("api"));
+    }
+
+    #[test]
+    fn a_constantly_ready_slot_cannot_starve_the_others_within_a_full_rotation() {
+        // Simulates slot 1 (e.g. `network`) being ready on every poll while
+        // slot 2 (e.g. `api`) only becomes ready on the third poll. Even
+        // though slot 1 always has an item, the rotating cursor guarantees
+        // slot 2 gets checked first at least once every 3 polls, so its
+        // event isn't starved out.
+        let mut cursor = 0u8;
+        let mut api_delivered_within = None;
+
+        for attempt in 0..3u32 {
+            let api_ready = attempt == 2;
+            let (mut a, mut b, mut c) = (None, Some("network-event"), if api_ready { Some("api-event") } else { None });
+
+            let picked = pick_buffered(cursor, [&mut a, &mut b, &mut c]);
+            cursor = (cursor + 1) % 3;
+
+            if picked == Some("api-event") {
+                api_delivered_within = Some(attempt + 1);
+                break;
+            }
+        }
+
+        assert_eq!(api_delivered_within, Some(3));
+    }
+
+    #[test]
+    fn an_item_buffered_but_not_picked_is_delivered_on_a_later_poll() {
+        // Round 1: both network and api are ready; cursor favors network,
+        // so api's item must survive into round 2 rather than being
+        // dropped when that round's locals go out of scope.
+        let mut network_slot = Some("network-event");
+        let mut api_slot = Some("api-event");
+        let mut timeout_slot: Option<&'static str> = None;
+
+        let first = pick_buffered(1, [&mut timeout_slot, &mut network_slot, &mut api_slot]);
+        assert_eq!(first, Some("network-event"));
+        assert_eq!(api_slot, Some("api-event"));
+
+        let second = pick_buffered(2, [&mut timeout_slot, &mut network_slot, &mut api_slot]);
+        assert_eq!(second, Some("api-event"));
+    }
 }
\ No newline at end of file